@@ -0,0 +1,9 @@
+use thiserror::Error;
+
+use super::task::TaskId;
+
+#[derive(Debug, Error)]
+pub enum TaskError {
+    #[error("task `{0}` not found")]
+    UnexistingTask(TaskId),
+}
@@ -0,0 +1,5 @@
+pub mod error;
+pub mod task;
+pub mod task_store;
+
+pub type Result<T> = std::result::Result<T, error::TaskError>;
@@ -0,0 +1,46 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::index_resolver::IndexUid;
+
+/// Unique identifier of a task, assigned in registration order by the store.
+pub type TaskId = u64;
+
+/// A task and the history of events that happened to it since it was registered.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Task {
+    pub id: TaskId,
+    pub index_uid: IndexUid,
+    pub content: TaskContent,
+    pub events: Vec<TaskEvent>,
+}
+
+/// What a task is asking the engine to do.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TaskContent {
+    CreateIndex { primary_key: Option<String> },
+    DeleteIndex,
+}
+
+/// Something that happened to a task since it was created. A task's current status is always
+/// derived from its last event.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum TaskEvent {
+    Created(DateTime<Utc>),
+    Processing(DateTime<Utc>),
+    Succeeded { timestamp: DateTime<Utc> },
+    Failed { error: String, timestamp: DateTime<Utc> },
+    /// The task was canceled while it was still enqueued, before the scheduler ever picked it
+    /// up. A task that already transitioned to `Processing` or further cannot be canceled.
+    Canceled(DateTime<Utc>),
+}
+
+/// The status of a task, derived from the last event in its history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum TaskStatus {
+    Enqueued,
+    Processing,
+    Succeeded,
+    Failed,
+    Canceled,
+}
@@ -1,16 +1,17 @@
 mod store;
 
 use std::cmp::Reverse;
-use std::collections::{BinaryHeap, HashSet};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::mem::Discriminant;
 use std::path::Path;
 use std::sync::Arc;
 
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use log::debug;
 use tokio::sync::RwLock;
 
 use crate::index_resolver::IndexUid;
-use crate::tasks::task::TaskEvent;
+use crate::tasks::task::{TaskEvent, TaskStatus};
 
 use super::error::TaskError;
 use super::task::{Task, TaskContent, TaskId};
@@ -21,10 +22,33 @@ pub use store::test::MockStore as Store;
 #[cfg(not(test))]
 pub use store::Store;
 
+/// Returns the status of a task, derived from its last event.
+fn task_status(task: &Task) -> TaskStatus {
+    match task.events.last() {
+        Some(TaskEvent::Created(_)) | None => TaskStatus::Enqueued,
+        Some(TaskEvent::Processing(_)) => TaskStatus::Processing,
+        Some(TaskEvent::Succeeded { .. }) => TaskStatus::Succeeded,
+        Some(TaskEvent::Failed { .. }) => TaskStatus::Failed,
+        Some(TaskEvent::Canceled(_)) => TaskStatus::Canceled,
+    }
+}
+
+/// Returns the date the task was created at, i.e. the timestamp of its first event.
+fn task_created_at(task: &Task) -> Option<DateTime<Utc>> {
+    match task.events.first() {
+        Some(TaskEvent::Created(ts)) => Some(*ts),
+        _ => None,
+    }
+}
+
 /// Defines constraints to be applied when querying for Tasks from the store.
 #[derive(Default, Debug)]
 pub struct TaskFilter {
     indexes: Option<HashSet<String>>,
+    statuses: Option<HashSet<TaskStatus>>,
+    content_kinds: Option<HashSet<Discriminant<TaskContent>>>,
+    created_after: Option<DateTime<Utc>>,
+    created_before: Option<DateTime<Utc>>,
 }
 
 impl TaskFilter {
@@ -33,6 +57,24 @@ impl TaskFilter {
             .as_ref()
             .map(|indexes| indexes.contains(&*task.index_uid))
             .unwrap_or(true)
+            && self
+                .statuses
+                .as_ref()
+                .map(|statuses| statuses.contains(&task_status(task)))
+                .unwrap_or(true)
+            && self
+                .content_kinds
+                .as_ref()
+                .map(|kinds| kinds.contains(&std::mem::discriminant(&task.content)))
+                .unwrap_or(true)
+            && self
+                .created_after
+                .map(|after| task_created_at(task).map_or(false, |ts| ts >= after))
+                .unwrap_or(true)
+            && self
+                .created_before
+                .map(|before| task_created_at(task).map_or(false, |ts| ts <= before))
+                .unwrap_or(true)
     }
 
     /// Adds an index to the filter, so the filter must match this index.
@@ -41,6 +83,30 @@ impl TaskFilter {
             .get_or_insert_with(Default::default)
             .insert(index);
     }
+
+    /// Adds a status to the filter, so the filter must match one of the registered statuses.
+    pub fn filter_status(&mut self, status: TaskStatus) {
+        self.statuses.get_or_insert_with(Default::default).insert(status);
+    }
+
+    /// Restricts the filter to tasks whose content is of the same kind as `content`, e.g. pass a
+    /// `TaskContent::CreateIndex { .. }` to only match index creation tasks. The value carried by
+    /// `content` itself is ignored, only its variant is considered.
+    pub fn filter_content_kind(&mut self, content: &TaskContent) {
+        self.content_kinds
+            .get_or_insert_with(Default::default)
+            .insert(std::mem::discriminant(content));
+    }
+
+    /// Restricts the filter to tasks created at or after `after`.
+    pub fn filter_after(&mut self, after: DateTime<Utc>) {
+        self.created_after = Some(after);
+    }
+
+    /// Restricts the filter to tasks created at or before `before`.
+    pub fn filter_before(&mut self, before: DateTime<Utc>) {
+        self.created_before = Some(before);
+    }
 }
 
 pub struct TaskStore {
@@ -57,13 +123,36 @@ impl Clone for TaskStore {
     }
 }
 
+/// Returns `true` if the task's last event indicates that it has not finished processing yet,
+/// i.e. it is either still enqueued or being processed, and must stay in the pending queue.
+fn is_pending(task: &Task) -> bool {
+    !matches!(
+        task_status(task),
+        TaskStatus::Succeeded | TaskStatus::Failed | TaskStatus::Canceled
+    )
+}
+
 impl TaskStore {
     pub fn new(path: impl AsRef<Path>, size: usize) -> Result<Self> {
         let store = Arc::new(Store::new(path, size)?);
-        let pending_queue = Arc::default();
+
+        // Tasks that were registered but never completed before the previous shutdown must be
+        // put back in the pending queue, otherwise they become invisible to the scheduler even
+        // though they are still sitting in the store.
+        let pending_queue = {
+            let txn = store.rtxn()?;
+            let unfinished_tasks = store.list_tasks(&txn, None, None, None)?;
+
+            unfinished_tasks
+                .into_iter()
+                .filter(is_pending)
+                .map(|task| Reverse(task.id))
+                .collect::<BinaryHeap<_>>()
+        };
+
         Ok(Self {
             store,
-            pending_queue,
+            pending_queue: Arc::new(RwLock::new(pending_queue)),
         })
     }
 
@@ -174,6 +263,65 @@ impl TaskStore {
         })
         .await?
     }
+
+    /// Cancels the tasks referenced by `ids`, provided they have not started processing yet.
+    ///
+    /// A task is only ever mutated while it is still `Enqueued`: one that is already
+    /// `Processing`, or that already reached a terminal state (including one that was already
+    /// canceled), is left untouched and reported as not cancelable so that callers can surface
+    /// partial success back to the user.
+    pub async fn cancel_tasks(
+        &self,
+        ids: HashSet<TaskId>,
+    ) -> Result<HashMap<TaskId, TaskCancelationResult>> {
+        let store = self.store.clone();
+        let results = tokio::task::spawn_blocking(move || -> Result<_> {
+            let mut txn = store.wtxn()?;
+            let mut results = HashMap::with_capacity(ids.len());
+
+            for id in ids {
+                let result = match store.get(&txn, id)? {
+                    Some(mut task) => match task.events.last() {
+                        Some(TaskEvent::Created(_)) => {
+                            task.events.push(TaskEvent::Canceled(Utc::now()));
+                            store.put(&mut txn, &task)?;
+                            TaskCancelationResult::Canceled
+                        }
+                        _ => TaskCancelationResult::NotCancelable,
+                    },
+                    None => TaskCancelationResult::Unknown,
+                };
+                results.insert(id, result);
+            }
+
+            txn.commit()?;
+
+            Ok(results)
+        })
+        .await??;
+
+        let mut pending_queue = self.pending_queue.write().await;
+        *pending_queue = pending_queue
+            .drain()
+            .filter(|id| !matches!(results.get(&id.0), Some(TaskCancelationResult::Canceled)))
+            .collect();
+        drop(pending_queue);
+
+        Ok(results)
+    }
+}
+
+/// The outcome of a single task cancelation request, as returned by
+/// [`TaskStore::cancel_tasks`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskCancelationResult {
+    /// The task was still enqueued and has been marked as canceled.
+    Canceled,
+    /// The task was found but had already started processing, or already reached a terminal
+    /// state, so it was left untouched.
+    NotCancelable,
+    /// No task with this id exists in the store.
+    Unknown,
 }
 
 #[cfg(test)]
@@ -224,6 +372,19 @@ pub mod test {
             }
         }
 
+        pub async fn cancel_tasks(
+            &self,
+            ids: HashSet<TaskId>,
+        ) -> Result<HashMap<TaskId, TaskCancelationResult>> {
+            match self {
+                Self::Real(s) => s.cancel_tasks(ids).await,
+                Self::Mock(m) => unsafe {
+                    m.get::<_, Result<HashMap<TaskId, TaskCancelationResult>>>("cancel_tasks")
+                        .call(ids)
+                },
+            }
+        }
+
         pub async fn get_task(&self, id: TaskId, filter: Option<TaskFilter>) -> Result<Task> {
             match self {
                 Self::Real(s) => s.get_task(id, filter).await,
@@ -268,6 +429,188 @@ pub mod test {
         }
     }
 
+    #[actix_rt::test]
+    async fn test_recover_pending_tasks_on_restart() {
+        let temp_dir = tempdir().unwrap();
+
+        let store = TaskStore::new(temp_dir.path(), 4096 * 1000).unwrap();
+        let first_task = store
+            .register(
+                IndexUid::new_unchecked("test"),
+                TaskContent::CreateIndex { primary_key: None },
+            )
+            .await
+            .unwrap();
+        store
+            .register(
+                IndexUid::new_unchecked("test"),
+                TaskContent::CreateIndex { primary_key: None },
+            )
+            .await
+            .unwrap();
+
+        // simulate a restart: the store is dropped and reopened on the same path, the
+        // in-memory pending queue is lost in the process.
+        drop(store);
+
+        let store = TaskStore::new(temp_dir.path(), 4096 * 1000).unwrap();
+
+        assert_eq!(store.peek_pending().await, Some(first_task.id));
+    }
+
+    #[actix_rt::test]
+    async fn test_cancel_tasks() {
+        let temp_dir = tempdir().unwrap();
+        let store = TaskStore::new(temp_dir.path(), 4096 * 1000).unwrap();
+
+        let pending = store
+            .register(
+                IndexUid::new_unchecked("test"),
+                TaskContent::CreateIndex { primary_key: None },
+            )
+            .await
+            .unwrap();
+        let racing = store
+            .register(
+                IndexUid::new_unchecked("test"),
+                TaskContent::CreateIndex { primary_key: None },
+            )
+            .await
+            .unwrap();
+
+        // simulate the race where a task transitions to `Processing` between the moment it was
+        // enqueued and the moment the cancelation request reaches the store.
+        let mut processing_task = store.get_task(racing.id, None).await.unwrap();
+        processing_task.events.push(TaskEvent::Processing(Utc::now()));
+        store.update_tasks(vec![processing_task]).await.unwrap();
+
+        let ids = HashSet::from([pending.id, racing.id, 42]);
+        let results = store.cancel_tasks(ids).await.unwrap();
+
+        assert_eq!(results[&pending.id], TaskCancelationResult::Canceled);
+        assert_eq!(results[&racing.id], TaskCancelationResult::NotCancelable);
+        assert_eq!(results[&42], TaskCancelationResult::Unknown);
+
+        // the task that raced to `Processing` must still be reachable by the scheduler, the
+        // canceled one must not.
+        assert_eq!(store.peek_pending().await, Some(racing.id));
+
+        let task = store.get_task(pending.id, None).await.unwrap();
+        assert!(matches!(task.events.last(), Some(TaskEvent::Canceled(_))));
+    }
+
+    /// Property-based counterpart of `test_cancel_tasks`: for an arbitrary number of registered
+    /// tasks, an arbitrary subset of them races to `Processing` before the cancelation request
+    /// reaches the store, and `cancel_tasks` must resolve each one correctly regardless of the
+    /// interleaving.
+    #[test]
+    fn test_cancel_tasks_race_with_processing() {
+        use proptest::prelude::*;
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        let mut runner = TestRunner::new(Config::default());
+
+        runner
+            .run(
+                &proptest::collection::vec(any::<bool>(), 1..10),
+                |races| {
+                    rt.block_on(async {
+                        let temp_dir = tempdir().unwrap();
+                        let store = TaskStore::new(temp_dir.path(), 4096 * 1000).unwrap();
+
+                        let mut tasks = Vec::with_capacity(races.len());
+                        for _ in 0..races.len() {
+                            let task = store
+                                .register(
+                                    IndexUid::new_unchecked("test"),
+                                    TaskContent::CreateIndex { primary_key: None },
+                                )
+                                .await
+                                .unwrap();
+                            tasks.push(task);
+                        }
+
+                        // for every task whose matching entry in `races` is `true`, simulate the
+                        // scheduler winning the race and moving it to `Processing` first.
+                        for (task, &racing) in tasks.iter().zip(races.iter()) {
+                            if racing {
+                                let mut processing_task =
+                                    store.get_task(task.id, None).await.unwrap();
+                                processing_task.events.push(TaskEvent::Processing(Utc::now()));
+                                store.update_tasks(vec![processing_task]).await.unwrap();
+                            }
+                        }
+
+                        let unknown_id = tasks.iter().map(|t| t.id).max().unwrap_or(0) + 1;
+                        let ids: HashSet<TaskId> =
+                            tasks.iter().map(|t| t.id).chain(Some(unknown_id)).collect();
+                        let results = store.cancel_tasks(ids).await.unwrap();
+
+                        for (task, &racing) in tasks.iter().zip(races.iter()) {
+                            let expected = if racing {
+                                TaskCancelationResult::NotCancelable
+                            } else {
+                                TaskCancelationResult::Canceled
+                            };
+                            prop_assert_eq!(results[&task.id], expected);
+                        }
+                        prop_assert_eq!(results[&unknown_id], TaskCancelationResult::Unknown);
+
+                        // tasks that won the race must still be processing, the others must have
+                        // been canceled.
+                        for (task, &racing) in tasks.iter().zip(races.iter()) {
+                            if racing {
+                                let processing = store.get_task(task.id, None).await.unwrap();
+                                prop_assert!(matches!(
+                                    processing.events.last(),
+                                    Some(TaskEvent::Processing(_))
+                                ));
+                            } else {
+                                let canceled = store.get_task(task.id, None).await.unwrap();
+                                prop_assert!(matches!(
+                                    canceled.events.last(),
+                                    Some(TaskEvent::Canceled(_))
+                                ));
+                            }
+                        }
+
+                        Ok(())
+                    })
+                },
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_task_filter_status_content_kind_and_time_range() {
+        let task = Task {
+            id: 0,
+            index_uid: IndexUid::new_unchecked("test"),
+            content: TaskContent::CreateIndex { primary_key: None },
+            events: vec![TaskEvent::Created(Utc::now())],
+        };
+
+        let mut filter = TaskFilter::default();
+        filter.filter_status(TaskStatus::Enqueued);
+        assert!(filter.pass(&task));
+
+        let mut filter = TaskFilter::default();
+        filter.filter_status(TaskStatus::Succeeded);
+        assert!(!filter.pass(&task));
+
+        let mut filter = TaskFilter::default();
+        filter.filter_content_kind(&TaskContent::CreateIndex { primary_key: None });
+        assert!(filter.pass(&task));
+
+        let mut filter = TaskFilter::default();
+        filter.filter_after(Utc::now() + chrono::Duration::minutes(1));
+        assert!(!filter.pass(&task));
+
+        let mut filter = TaskFilter::default();
+        filter.filter_before(Utc::now() + chrono::Duration::minutes(1));
+        assert!(filter.pass(&task));
+    }
+
     #[test]
     fn test_increment_task_id() {
         let mut runner = TestRunner::new(Config::default());
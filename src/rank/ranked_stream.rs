@@ -1,3 +1,4 @@
+use std::collections::BinaryHeap;
 use std::ops::Range;
 use std::rc::Rc;
 use std::{mem, vec, cmp};
@@ -11,13 +12,54 @@ use crate::metadata::Metadata;
 use crate::metadata::ops::OpBuilder;
 use crate::rank::criterion::Criterion;
 use crate::rank::Document;
-use crate::Match;
+use crate::{DocumentId, Match};
+
+/// Maps a query term's length to the maximum edit distance (number of typos) that is tolerated
+/// when matching it against the index, so short terms stay precise while longer ones stay
+/// forgiving of typos.
+#[derive(Debug, Clone)]
+pub struct TypoPolicy {
+    /// terms strictly shorter than this many characters must match exactly.
+    exact_len: usize,
+    /// terms strictly shorter than this many characters allow at most one typo.
+    one_typo_len: usize,
+    /// terms at least `one_typo_len` characters long allow at most this many typos.
+    max_typos: u8,
+}
+
+impl Default for TypoPolicy {
+    fn default() -> Self {
+        TypoPolicy {
+            exact_len: 4,
+            one_typo_len: 8,
+            max_typos: 2,
+        }
+    }
+}
+
+impl TypoPolicy {
+    pub fn new(exact_len: usize, one_typo_len: usize, max_typos: u8) -> Self {
+        TypoPolicy { exact_len, one_typo_len, max_typos }
+    }
+
+    /// Returns the maximum edit distance tolerated for a term of the given length.
+    fn max_distance(&self, term_len: usize) -> u8 {
+        if term_len < self.exact_len {
+            0
+        } else if term_len < self.one_typo_len {
+            cmp::min(1, self.max_typos)
+        } else {
+            self.max_typos
+        }
+    }
+}
 
 #[derive(Clone)]
 pub struct RankedStreamBuilder<'m, C> {
     metadata: &'m Metadata,
     automatons: Vec<Rc<DfaExt>>,
     criteria: Vec<C>,
+    typo_policy: TypoPolicy,
 }
 
 impl<'m, C> RankedStreamBuilder<'m, C> {
@@ -26,6 +68,7 @@ impl<'m, C> RankedStreamBuilder<'m, C> {
             metadata: metadata,
             automatons: automatons.into_iter().map(Rc::new).collect(),
             criteria: Vec::new(), // hummm...  prefer the criterion::default() ones !
+            typo_policy: TypoPolicy::default(),
         }
     }
 
@@ -33,6 +76,12 @@ impl<'m, C> RankedStreamBuilder<'m, C> {
         self.criteria = criteria;
     }
 
+    /// Sets the typo tolerance policy applied to every automaton match. Defaults to
+    /// `TypoPolicy::default()`.
+    pub fn typo_policy(&mut self, typo_policy: TypoPolicy) {
+        self.typo_policy = typo_policy;
+    }
+
     pub fn build(&self) -> RankedStream<C> {
         let mut builder = OpBuilder::with_automatons(self.automatons.clone());
         builder.push(self.metadata);
@@ -41,6 +90,7 @@ impl<'m, C> RankedStreamBuilder<'m, C> {
             stream: builder.union(),
             automatons: &self.automatons,
             criteria: &self.criteria,
+            typo_policy: self.typo_policy.clone(),
         }
     }
 }
@@ -49,18 +99,126 @@ pub struct RankedStream<'a, 'm, C> {
     stream: crate::metadata::ops::Union<'m>,
     automatons: &'a [Rc<DfaExt>],
     criteria: &'a [C],
+    typo_policy: TypoPolicy,
+}
+
+/// Wraps a `Document` alongside the criteria chain it must be ranked with, so it can be ordered
+/// by a `BinaryHeap` without the heap itself knowing anything about criteria.
+struct RankedDocument<'a, C> {
+    document: Document,
+    criteria: &'a [C],
+}
+
+impl<'a, C: Criterion> PartialEq for RankedDocument<'a, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == cmp::Ordering::Equal
+    }
+}
+
+impl<'a, C: Criterion> Eq for RankedDocument<'a, C> {}
+
+impl<'a, C: Criterion> PartialOrd for RankedDocument<'a, C> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a, C: Criterion> Ord for RankedDocument<'a, C> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        for criterion in self.criteria {
+            match criterion.evaluate(&self.document, &other.document) {
+                cmp::Ordering::Equal => continue,
+                ordering => return ordering,
+            }
+        }
+        cmp::Ordering::Equal
+    }
+}
+
+/// Folds every in-progress document in `matches` into the bounded top-`k` `heap`, draining
+/// `matches` in the process. A candidate that can't beat the current worst kept document (the
+/// heap's max, once the heap is full) is dropped immediately.
+fn compact_into_heap<'c, C: Criterion>(
+    matches: &mut FnvHashMap<DocumentId, Vec<Match>>,
+    heap: &mut BinaryHeap<RankedDocument<'c, C>>,
+    k: usize,
+    criteria: &'c [C],
+) {
+    for (id, mut doc_matches) in matches.drain() {
+        doc_matches.sort_unstable();
+        let document = unsafe { Document::from_sorted_matches(id, doc_matches) };
+        let candidate = RankedDocument { document, criteria };
+
+        if heap.len() < k {
+            heap.push(candidate);
+        } else if heap.peek().map_or(false, |worst| candidate < *worst) {
+            heap.pop();
+            heap.push(candidate);
+        }
+    }
+}
+
+/// Fully orders the (already bounded) top-k candidates in `heap` by the criteria chain, then
+/// slices the result down to `range`.
+fn rank_heap<C: Criterion>(
+    heap: BinaryHeap<RankedDocument<C>>,
+    criteria: &[C],
+    range: Range<usize>,
+) -> Vec<Document> {
+    let mut documents: Vec<_> = heap.into_iter().map(|ranked| ranked.document).collect();
+
+    let mut groups = vec![documents.as_mut_slice()];
+
+    for criterion in criteria {
+        let tmp_groups = mem::replace(&mut groups, Vec::new());
+
+        for group in tmp_groups {
+            group.sort_unstable_by(|a, b| criterion.evaluate(a, b));
+            for group in GroupByMut::new(group, |a, b| criterion.eq(a, b)) {
+                groups.push(group);
+            }
+        }
+    }
+
+    let start = cmp::min(range.start, documents.len());
+    let mut documents = documents.split_off(start);
+    documents.truncate(range.len());
+    documents
 }
 
 impl<'a, 'm, C> RankedStream<'a, 'm, C> {
     pub fn retrieve_documents(&mut self, range: Range<usize>) -> Vec<Document>
     where C: Criterion
     {
-        let mut matches = FnvHashMap::default();
+        let k = range.end;
+        let criteria = self.criteria;
+
+        // The union stream yields matches grouped by dictionary string, not by document: a
+        // document that matches several query terms gets its matches spread across several,
+        // possibly far apart, calls to `stream.next()`. So `matches` can only be compacted into
+        // the bounded top-k `heap` once the stream is fully exhausted and every document's
+        // matches are known to be complete; compacting mid-stream would fold a document in from
+        // a partial match set, and it would then be treated as a brand new, independently scored
+        // entry if more of its matches arrived afterwards. This keeps the top-k ranking itself
+        // bounded to `k` documents, at the cost of the in-progress `matches` map growing with the
+        // full match set for the query while the stream is being consumed.
+        let mut matches: FnvHashMap<DocumentId, Vec<Match>> = FnvHashMap::default();
+        let mut heap: BinaryHeap<RankedDocument<C>> = BinaryHeap::with_capacity(k + 1);
 
         while let Some((string, indexed_values)) = self.stream.next() {
             for iv in indexed_values {
                 let automaton = &self.automatons[iv.index];
                 let distance = automaton.eval(string).to_u8();
+
+                if distance > self.typo_policy.max_distance(automaton.query_len()) {
+                    continue;
+                }
+
+                // `is_exact` is computed from the raw edit distance, not from the tolerated
+                // `max_distance` cutoff above, so a looser typo policy only widens which matches
+                // are kept and never turns a typo-match into an exact one. Exact matches keep
+                // ranking first through the (distance, is_exact)-aware criteria regardless of
+                // how permissive the configured policy is.
                 let is_exact = distance == 0 && string.len() == automaton.query_len();
 
                 for di in iv.doc_indexes.as_slice() {
@@ -76,23 +234,66 @@ impl<'a, 'm, C> RankedStream<'a, 'm, C> {
             }
         }
 
-        // collect matches from an HashMap into a Vec
-        let mut documents: Vec<_> = matches.into_iter().map(|(id, mut matches)| {
-            matches.sort_unstable();
-            unsafe { Document::from_sorted_matches(id, matches) }
-        }).collect();
+        compact_into_heap(&mut matches, &mut heap, k, criteria);
+
+        rank_heap(heap, self.criteria, range)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ranks documents by their id, so fixtures produce a deterministic, easy to assert order.
+    #[derive(Clone)]
+    struct ById;
+
+    impl Criterion for ById {
+        fn evaluate(&self, a: &Document, b: &Document) -> cmp::Ordering {
+            a.id.cmp(&b.id)
+        }
+
+        fn eq(&self, a: &Document, b: &Document) -> bool {
+            a.id == b.id
+        }
+    }
+
+    fn make_matches(count: usize) -> Vec<Match> {
+        (0..count)
+            .map(|i| Match {
+                query_index: 0,
+                distance: 0,
+                attribute: 0,
+                attribute_index: i as u16,
+                is_exact: true,
+            })
+            .collect()
+    }
+
+    /// The pre-bounded-heap algorithm (full materialization, then a grouped sort restricted to
+    /// `range`), kept here only to check the bounded rewrite against it.
+    fn naive_rank<C: Criterion>(
+        matches: FnvHashMap<DocumentId, Vec<Match>>,
+        criteria: &[C],
+        range: Range<usize>,
+    ) -> Vec<Document> {
+        let mut documents: Vec<_> = matches
+            .into_iter()
+            .map(|(id, mut matches)| {
+                matches.sort_unstable();
+                unsafe { Document::from_sorted_matches(id, matches) }
+            })
+            .collect();
 
         let mut groups = vec![documents.as_mut_slice()];
 
-        for criterion in self.criteria {
+        for criterion in criteria {
             let tmp_groups = mem::replace(&mut groups, Vec::new());
             let mut current_range = Range { start: 0, end: 0 };
 
             'grp: for group in tmp_groups {
                 current_range.end += group.len();
 
-                // if a part of the current group is in the range returned
-                // we must sort it and emit the sub-groups
                 if current_range.contains(&range.start) {
                     group.sort_unstable_by(|a, b| criterion.evaluate(a, b));
                     for group in GroupByMut::new(group, |a, b| criterion.eq(a, b)) {
@@ -107,11 +308,39 @@ impl<'a, 'm, C> RankedStream<'a, 'm, C> {
             }
         }
 
-        // TODO find a better algorithm, here we allocate for too many documents
-        //      and we do a useless allocation, we should reuse the documents Vec
         let start = cmp::min(range.start, documents.len());
         let mut documents = documents.split_off(start);
         documents.truncate(range.len());
         documents
     }
+
+    fn bounded_rank<C: Criterion>(
+        mut matches: FnvHashMap<DocumentId, Vec<Match>>,
+        criteria: &[C],
+        range: Range<usize>,
+    ) -> Vec<Document> {
+        let k = range.end;
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        compact_into_heap(&mut matches, &mut heap, k, criteria);
+        rank_heap(heap, criteria, range)
+    }
+
+    #[test]
+    fn test_bounded_ranking_matches_naive_ranking_on_fixture() {
+        let criteria = vec![ById];
+
+        let mut fixture = FnvHashMap::default();
+        for id in [5u64, 1, 4, 2, 3, 0] {
+            fixture.insert(id, make_matches(2));
+        }
+
+        for range in [0..3, 1..4, 0..6, 2..2, 4..10] {
+            let expected = naive_rank(fixture.clone(), &criteria, range.clone());
+            let actual = bounded_rank(fixture.clone(), &criteria, range.clone());
+
+            let expected_ids: Vec<_> = expected.iter().map(|d| d.id).collect();
+            let actual_ids: Vec<_> = actual.iter().map(|d| d.id).collect();
+            assert_eq!(expected_ids, actual_ids, "mismatch for range {:?}", range);
+        }
+    }
 }